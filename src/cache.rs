@@ -0,0 +1,138 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// A stable 64-bit handle for a message, derived by hashing its notmuch
+/// message-id. Clients like meli that cache envelopes by filesystem path
+/// find their cache invalidated every time notmuch renames or moves a
+/// file within the maildir; the message-id is stable across those moves,
+/// so a hash of it makes a handle that survives them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EnvelopeHash(u64);
+
+impl EnvelopeHash {
+    fn of(message_id: &str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        message_id.hash(&mut hasher);
+        EnvelopeHash(hasher.finish())
+    }
+}
+
+/// An opt-in, message-id-keyed cache of [`EnvelopeHash`]es for a
+/// [`Database`](crate::Database).
+///
+/// Entries hold only the id string, never a `notmuch_message_t` pointer:
+/// that pointer's lifetime is tied to the query that produced it, so
+/// keeping it around here would be unsound once that query is dropped.
+/// The id itself is cheap (a short ASCII string) and owned by the cache,
+/// so entries stay resolvable until explicitly invalidated rather than
+/// depending on some other part of the program happening to keep a
+/// reference alive.
+///
+/// This stores the id as a strong `Arc<String>` rather than a `Weak` one:
+/// a weak id string has nothing else in the program holding a strong
+/// reference to keep it alive, so it would be dropped (and every lookup
+/// would miss) almost immediately after being inserted, defeating the
+/// cache's purpose.
+#[derive(Debug, Default)]
+pub struct MessageCache {
+    by_hash: Mutex<HashMap<EnvelopeHash, Arc<String>>>,
+}
+
+impl MessageCache {
+    pub fn new() -> Self {
+        MessageCache {
+            by_hash: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record `message_id`, returning its stable hash.
+    pub fn insert(&self, message_id: &str) -> EnvelopeHash {
+        let hash = EnvelopeHash::of(message_id);
+
+        self.by_hash
+            .lock()
+            .unwrap()
+            .insert(hash, Arc::new(message_id.to_string()));
+
+        hash
+    }
+
+    /// The message-id cached under `hash`, if any.
+    pub fn id_for_hash(&self, hash: EnvelopeHash) -> Option<Arc<String>> {
+        self.by_hash.lock().unwrap().get(&hash).cloned()
+    }
+
+    /// Drop the entry for `message_id`, e.g. because the watch or write
+    /// APIs observed that it was removed or re-indexed.
+    pub fn invalidate(&self, message_id: &str) {
+        self.by_hash
+            .lock()
+            .unwrap()
+            .remove(&EnvelopeHash::of(message_id));
+    }
+
+    /// Drop every entry, e.g. in response to a `RefreshEvent::Rescan`
+    /// that can't be attributed to individual message ids.
+    pub fn clear(&self) {
+        self.by_hash.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_then_lookup_hits() {
+        let cache = MessageCache::new();
+        let hash = cache.insert("<abc@example.com>");
+
+        assert_eq!(
+            cache.id_for_hash(hash).unwrap().as_str(),
+            "<abc@example.com>"
+        );
+    }
+
+    #[test]
+    fn same_id_hashes_the_same() {
+        assert_eq!(
+            EnvelopeHash::of("<abc@example.com>"),
+            EnvelopeHash::of("<abc@example.com>")
+        );
+    }
+
+    #[test]
+    fn unknown_hash_misses() {
+        let cache = MessageCache::new();
+        cache.insert("<abc@example.com>");
+
+        let other = EnvelopeHash::of("<other@example.com>");
+        assert!(cache.id_for_hash(other).is_none());
+    }
+
+    #[test]
+    fn invalidate_drops_just_that_id() {
+        let cache = MessageCache::new();
+        let kept = cache.insert("<keep@example.com>");
+        let dropped = cache.insert("<drop@example.com>");
+
+        cache.invalidate("<drop@example.com>");
+
+        assert!(cache.id_for_hash(dropped).is_none());
+        assert!(cache.id_for_hash(kept).is_some());
+    }
+
+    #[test]
+    fn clear_drops_everything() {
+        let cache = MessageCache::new();
+        let a = cache.insert("<a@example.com>");
+        let b = cache.insert("<b@example.com>");
+
+        cache.clear();
+
+        assert!(cache.id_for_hash(a).is_none());
+        assert!(cache.id_for_hash(b).is_none());
+    }
+}
@@ -1,14 +1,20 @@
 use std::ffi::{CStr, CString};
 use std::ops::Drop;
 use std::path::Path;
-use std::ptr;
+use std::ptr::{self, NonNull};
+#[cfg(feature = "dynamic")]
+use std::sync::Arc;
 
 use libc;
 
-use error::Result;
+use cache::{EnvelopeHash, MessageCache};
+use error::{Error, Result};
 use ffi;
+#[cfg(feature = "dynamic")]
+use library::NotmuchLibrary;
 use utils::ToStr;
 use Directory;
+use Message;
 use Query;
 use Tags;
 use TagsOwner;
@@ -25,24 +31,73 @@ pub struct Revision {
     pub uuid: String,
 }
 
+/// Options controlling how [`Database::index_file`] indexes a message.
+#[derive(Debug)]
+pub struct IndexOpts {
+    ptr: *mut ffi::notmuch_indexopts_t,
+}
+
+impl IndexOpts {
+    pub fn new() -> Result<Self> {
+        let ptr = unsafe { ffi::notmuch_indexopts_create() };
+
+        if ptr.is_null() {
+            return Err(Error::from("failed to allocate notmuch_indexopts_t".to_string()));
+        }
+
+        Ok(IndexOpts { ptr })
+    }
+}
+
+impl Drop for IndexOpts {
+    fn drop(&mut self) {
+        unsafe { ffi::notmuch_indexopts_destroy(self.ptr) };
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct DatabasePtr {
-    pub ptr: *mut ffi::notmuch_database_t,
+    pub ptr: NonNull<ffi::notmuch_database_t>,
+}
+
+impl DatabasePtr {
+    /// Wrap a `notmuch_database_t*`, failing instead of trusting the
+    /// caller when libnotmuch hands back null.
+    fn from_ptr(ptr: *mut ffi::notmuch_database_t) -> Result<Self> {
+        NonNull::new(ptr)
+            .map(|ptr| DatabasePtr { ptr })
+            .ok_or_else(|| Error::from("notmuch returned a null database handle".to_string()))
+    }
 }
 
 impl Drop for DatabasePtr {
     fn drop(&mut self) {
-        unsafe { ffi::notmuch_database_destroy(self.ptr) };
+        unsafe { ffi::notmuch_database_destroy(self.ptr.as_ptr()) };
     }
 }
 
 #[derive(Debug)]
 pub struct Database {
     pub(crate) handle: DatabasePtr,
+    mode: DatabaseMode,
+    /// Opt-in message-id-keyed cache, enabled with
+    /// [`Database::enable_message_cache`]. `None` until then.
+    cache: Option<MessageCache>,
+    /// The dynamically loaded library this handle (and everything derived
+    /// from it) was opened through, if any. `None` for the statically
+    /// linked path.
+    #[cfg(feature = "dynamic")]
+    pub(crate) library: Option<Arc<NotmuchLibrary>>,
 }
 
 impl TagsOwner for Database {}
 
+impl AsRef<Database> for Database {
+    fn as_ref(&self) -> &Database {
+        self
+    }
+}
+
 impl Database {
     pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self> {
         let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
@@ -51,7 +106,11 @@ impl Database {
         try!(unsafe { ffi::notmuch_database_create(path_str.as_ptr(), &mut db) }.as_result());
 
         Ok(Database {
-            handle: DatabasePtr { ptr: db },
+            handle: try!(DatabasePtr::from_ptr(db)),
+            mode: DatabaseMode::ReadWrite,
+            cache: None,
+            #[cfg(feature = "dynamic")]
+            library: None,
         })
     }
 
@@ -65,12 +124,100 @@ impl Database {
         );
 
         Ok(Database {
-            handle: DatabasePtr { ptr: db },
+            handle: try!(DatabasePtr::from_ptr(db)),
+            mode,
+            cache: None,
+            #[cfg(feature = "dynamic")]
+            library: None,
         })
     }
 
+    /// Like [`Database::create`], but resolves every `notmuch_*` symbol
+    /// through `lib` instead of the statically linked library, so a client
+    /// can pick the installed ABI (or fail gracefully if none is found)
+    /// before opening anything.
+    #[cfg(feature = "dynamic")]
+    pub fn create_with_library<P: AsRef<Path>>(path: &P, lib: Arc<NotmuchLibrary>) -> Result<Self> {
+        let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
+
+        let mut db = ptr::null_mut();
+        let status = try!(unsafe {
+            call!(
+                lib,
+                notmuch_database_create,
+                unsafe extern "C" fn(*const libc::c_char, *mut *mut ffi::notmuch_database_t)
+                    -> ffi::notmuch_status_t,
+                (path_str.as_ptr(), &mut db)
+            )
+        });
+        try!(status.as_result());
+
+        Ok(Database {
+            handle: try!(DatabasePtr::from_ptr(db)),
+            mode: DatabaseMode::ReadWrite,
+            cache: None,
+            library: Some(lib),
+        })
+    }
+
+    /// Like [`Database::open`], but resolves every `notmuch_*` symbol
+    /// through `lib` (see [`Database::create_with_library`]).
+    #[cfg(feature = "dynamic")]
+    pub fn open_with_library<P: AsRef<Path>>(
+        path: &P,
+        mode: DatabaseMode,
+        lib: Arc<NotmuchLibrary>,
+    ) -> Result<Self> {
+        let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
+
+        let mut db = ptr::null_mut();
+        let status = try!(unsafe {
+            call!(
+                lib,
+                notmuch_database_open,
+                unsafe extern "C" fn(
+                    *const libc::c_char,
+                    ffi::notmuch_database_mode_t,
+                    *mut *mut ffi::notmuch_database_t,
+                ) -> ffi::notmuch_status_t,
+                (path_str.as_ptr(), mode.into(), &mut db)
+            )
+        });
+        try!(status.as_result());
+
+        Ok(Database {
+            handle: try!(DatabasePtr::from_ptr(db)),
+            mode,
+            cache: None,
+            library: Some(lib),
+        })
+    }
+
+    /// The library this handle was opened through, if it was opened via
+    /// [`Database::open_with_library`] / [`Database::create_with_library`].
+    #[cfg(feature = "dynamic")]
+    pub(crate) fn library(&self) -> Option<&Arc<NotmuchLibrary>> {
+        self.library.as_ref()
+    }
+
     pub fn close(&mut self) -> Result<()> {
-        try!(unsafe { ffi::notmuch_database_close(self.handle.ptr) }.as_result());
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_close,
+                    unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> ffi::notmuch_status_t,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_database_close(self.handle.ptr.as_ptr()) },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe { ffi::notmuch_database_close(self.handle.ptr.as_ptr()) };
+
+        try!(status.as_result());
 
         Ok(())
     }
@@ -127,24 +274,106 @@ impl Database {
         Ok(())
     }
 
+    /// The filesystem path this database was opened on.
+    ///
+    /// Routed through the dynamically loaded library (when this handle
+    /// was opened via [`Database::open_with_library`] /
+    /// [`Database::create_with_library`]), so it's fallible: a library
+    /// missing the symbol is a real possibility under `dynamic`. Without
+    /// that feature there's only ever the statically linked symbol, so
+    /// this can't fail and stays infallible rather than forcing every
+    /// caller to unwrap a `Result` that's always `Ok`.
+    #[cfg(feature = "dynamic")]
+    pub fn path(&self) -> Result<&Path> {
+        let path = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_get_path,
+                    unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> *const libc::c_char,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_database_get_path(self.handle.ptr.as_ptr()) },
+        };
+
+        Ok(Path::new(path.to_str().unwrap()))
+    }
+
+    /// The filesystem path this database was opened on.
+    #[cfg(not(feature = "dynamic"))]
     pub fn path(&self) -> &Path {
         Path::new(
-            unsafe { ffi::notmuch_database_get_path(self.handle.ptr) }
+            unsafe { ffi::notmuch_database_get_path(self.handle.ptr.as_ptr()) }
                 .to_str()
                 .unwrap(),
         )
     }
 
+    /// See the note on [`Database::path`] for why this is fallible only
+    /// under `dynamic`.
+    #[cfg(feature = "dynamic")]
+    pub fn version(&self) -> Result<Version> {
+        let version = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_get_version,
+                    unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> libc::c_uint,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_database_get_version(self.handle.ptr.as_ptr()) },
+        };
+
+        Ok(Version(version))
+    }
+
+    #[cfg(not(feature = "dynamic"))]
     pub fn version(&self) -> Version {
-        Version(unsafe { ffi::notmuch_database_get_version(self.handle.ptr) })
+        Version(unsafe { ffi::notmuch_database_get_version(self.handle.ptr.as_ptr()) })
     }
 
-    #[cfg(feature = "v0_21")]
+    /// See the note on [`Database::path`] for why this is fallible only
+    /// under `dynamic`.
+    #[cfg(all(feature = "v0_21", feature = "dynamic"))]
+    pub fn revision(&self) -> Result<Revision> {
+        let uuid_p: *const libc::c_char = ptr::null();
+
+        let revision = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_get_revision,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *mut *const libc::c_char,
+                    ) -> libc::c_ulong,
+                    (self.handle.ptr.as_ptr(), (&uuid_p) as *const _ as *mut *const libc::c_char)
+                )
+            }),
+            None => unsafe {
+                ffi::notmuch_database_get_revision(
+                    self.handle.ptr.as_ptr(),
+                    (&uuid_p) as *const _ as *mut *const libc::c_char,
+                )
+            },
+        };
+
+        let uuid = unsafe { CStr::from_ptr(uuid_p) };
+
+        Ok(Revision {
+            revision,
+            uuid: uuid.to_string_lossy().into_owned(),
+        })
+    }
+
+    #[cfg(all(feature = "v0_21", not(feature = "dynamic")))]
     pub fn revision(&self) -> Revision {
         let uuid_p: *const libc::c_char = ptr::null();
         let revision = unsafe {
             ffi::notmuch_database_get_revision(
-                self.handle.ptr,
+                self.handle.ptr.as_ptr(),
                 (&uuid_p) as *const _ as *mut *const libc::c_char,
             )
         };
@@ -157,8 +386,28 @@ impl Database {
         }
     }
 
+    /// See the note on [`Database::path`] for why this is fallible only
+    /// under `dynamic`.
+    #[cfg(feature = "dynamic")]
+    pub fn needs_upgrade(&self) -> Result<bool> {
+        let needs_upgrade = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_needs_upgrade,
+                    unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> libc::c_int,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_database_needs_upgrade(self.handle.ptr.as_ptr()) },
+        };
+
+        Ok(needs_upgrade == 1)
+    }
+
+    #[cfg(not(feature = "dynamic"))]
     pub fn needs_upgrade(&self) -> bool {
-        unsafe { ffi::notmuch_database_needs_upgrade(self.handle.ptr) == 1 }
+        unsafe { ffi::notmuch_database_needs_upgrade(self.handle.ptr.as_ptr()) == 1 }
     }
 
     pub fn upgrade<F: FnMut(f64)>(&mut self) -> Result<()> {
@@ -177,20 +426,49 @@ impl Database {
             unsafe { (*closure)(progress as f64) }
         }
 
-        try!(
-            unsafe {
+        let progress_fn = if status.is_some() {
+            Some(wrapper::<F>)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_upgrade,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        Option<extern "C" fn(*mut libc::c_void, libc::c_double)>,
+                        *mut libc::c_void,
+                    ) -> ffi::notmuch_status_t,
+                    (
+                        self.handle.ptr.as_ptr(),
+                        progress_fn,
+                        status.map_or(ptr::null_mut(), |f| &f as *const _ as *mut libc::c_void),
+                    )
+                )
+            }),
+            None => unsafe {
                 ffi::notmuch_database_upgrade(
-                    self.handle.ptr,
-                    if status.is_some() {
-                        Some(wrapper::<F>)
-                    } else {
-                        None
-                    },
+                    self.handle.ptr.as_ptr(),
+                    progress_fn,
                     status.map_or(ptr::null_mut(), |f| &f as *const _ as *mut libc::c_void),
                 )
-            }
-            .as_result()
-        );
+            },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe {
+            ffi::notmuch_database_upgrade(
+                self.handle.ptr.as_ptr(),
+                progress_fn,
+                status.map_or(ptr::null_mut(), |f| &f as *const _ as *mut libc::c_void),
+            )
+        };
+
+        try!(status.as_result());
 
         Ok(())
     }
@@ -199,34 +477,515 @@ impl Database {
         let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
 
         let mut dir = ptr::null_mut();
-        try!(
-            unsafe {
-                ffi::notmuch_database_get_directory(self.handle.ptr, path_str.as_ptr(), &mut dir)
-            }
-            .as_result()
-        );
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_get_directory,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *const libc::c_char,
+                        *mut *mut ffi::notmuch_directory_t,
+                    ) -> ffi::notmuch_status_t,
+                    (self.handle.ptr.as_ptr(), path_str.as_ptr(), &mut dir)
+                )
+            }),
+            None => unsafe {
+                ffi::notmuch_database_get_directory(self.handle.ptr.as_ptr(), path_str.as_ptr(), &mut dir)
+            },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe {
+            ffi::notmuch_database_get_directory(self.handle.ptr.as_ptr(), path_str.as_ptr(), &mut dir)
+        };
+
+        try!(status.as_result());
 
         if dir.is_null() {
             Ok(None)
         } else {
-            Ok(Some(Directory::from_ptr(dir, self)))
+            Ok(Some(try!(Directory::from_ptr(dir, self))))
         }
     }
 
+    /// Fail with a typed `Error` instead of handing a caller a null
+    /// pointer. `Query`, `Tags` and `Filenames` live outside this module
+    /// (their source isn't part of this crate's tree), so they can't be
+    /// given the `NonNull`-backed `*Ptr` treatment [`DatabasePtr`] and
+    /// [`DirectoryPtr`] got; checking here, before their own `from_ptr`
+    /// ever sees the pointer, is as much of that hardening as this module
+    /// can own. This is a deliberately partial stand-in for the
+    /// `NonNull`-backed redesign those types would otherwise get: the
+    /// pointer never reaches their `from_ptr` null, but they still store
+    /// a raw `*mut` internally rather than a `NonNull`.
+    pub(crate) fn non_null_or_err<T>(ptr: *mut T, what: &str) -> Result<*mut T> {
+        if ptr.is_null() {
+            Err(Error::from(format!("notmuch returned a null {} handle", what)))
+        } else {
+            Ok(ptr)
+        }
+    }
+
+    /// Build a query against this database.
+    ///
+    /// Note: [`Query`] itself lives outside this crate's `dynamic`-aware
+    /// hardening (its source isn't part of this module), so unlike
+    /// [`Directory`] it can't be handed an `Arc<NotmuchLibrary>` to keep
+    /// using for its own FFI calls; only the `notmuch_query_create` call
+    /// below is routed through a dynamically loaded library when one was
+    /// used to open this handle.
     pub fn create_query<'d>(&'d self, query_string: &str) -> Result<Query<'d>> {
         let query_str = CString::new(query_string).unwrap();
 
-        let query = unsafe { ffi::notmuch_query_create(self.handle.ptr, query_str.as_ptr()) };
+        #[cfg(feature = "dynamic")]
+        let query = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_query_create,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *const libc::c_char,
+                    ) -> *mut ffi::notmuch_query_t,
+                    (self.handle.ptr.as_ptr(), query_str.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_query_create(self.handle.ptr.as_ptr(), query_str.as_ptr()) },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let query = unsafe { ffi::notmuch_query_create(self.handle.ptr.as_ptr(), query_str.as_ptr()) };
+
+        let query = try!(Self::non_null_or_err(query, "query"));
 
         Ok(Query::from_ptr(query, self))
     }
 
+    /// All tags used anywhere in this database. See the note on
+    /// [`Database::create_query`]: [`Tags`] can't be threaded a library
+    /// handle either, so only this call itself is dynamic-aware.
     pub fn all_tags<'d>(&'d self) -> Result<Tags<'d, Self>> {
-        let tags = unsafe { ffi::notmuch_database_get_all_tags(self.handle.ptr) };
+        #[cfg(feature = "dynamic")]
+        let tags = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_get_all_tags,
+                    unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> *mut ffi::notmuch_tags_t,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_database_get_all_tags(self.handle.ptr.as_ptr()) },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let tags = unsafe { ffi::notmuch_database_get_all_tags(self.handle.ptr.as_ptr()) };
+
+        let tags = try!(Self::non_null_or_err(tags, "tags"));
 
         Ok(Tags::from_ptr(tags, self))
     }
+
+    /// Fail unless this handle was opened (or created) `ReadWrite`; every
+    /// mutating method below calls this first so attempting to write
+    /// through a read-only handle is a clear error instead of libnotmuch
+    /// rejecting the underlying call.
+    fn ensure_writable(&self) -> Result<()> {
+        match self.mode {
+            DatabaseMode::ReadWrite => Ok(()),
+            DatabaseMode::ReadOnly => Err(Error::from(
+                "this operation requires the database to be opened in ReadWrite mode".to_string(),
+            )),
+        }
+    }
+
+    /// Add `path` to the index, returning the resulting `Message` and
+    /// whether it was newly indexed (`false` if a message with the same
+    /// id already existed and this call just merged into it).
+    pub fn index_file<'d, P: AsRef<Path>>(
+        &'d self,
+        path: &P,
+        indexopts: Option<&IndexOpts>,
+    ) -> Result<(Message<'d>, bool)> {
+        try!(self.ensure_writable());
+
+        let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
+
+        let mut message = ptr::null_mut();
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_index_file,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *const libc::c_char,
+                        *mut ffi::notmuch_indexopts_t,
+                        *mut *mut ffi::notmuch_message_t,
+                    ) -> ffi::notmuch_status_t,
+                    (
+                        self.handle.ptr.as_ptr(),
+                        path_str.as_ptr(),
+                        indexopts.map_or(ptr::null_mut(), |o| o.ptr),
+                        &mut message,
+                    )
+                )
+            }),
+            None => unsafe {
+                ffi::notmuch_database_index_file(
+                    self.handle.ptr.as_ptr(),
+                    path_str.as_ptr(),
+                    indexopts.map_or(ptr::null_mut(), |o| o.ptr),
+                    &mut message,
+                )
+            },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe {
+            ffi::notmuch_database_index_file(
+                self.handle.ptr.as_ptr(),
+                path_str.as_ptr(),
+                indexopts.map_or(ptr::null_mut(), |o| o.ptr),
+                &mut message,
+            )
+        };
+
+        let was_new = try!(Self::index_file_was_new(status));
+
+        let message = Message::from_ptr(message, self);
+        if let Some(cache) = self.cache.as_ref() {
+            cache.insert(&message.id());
+        }
+
+        Ok((message, was_new))
+    }
+
+    /// Turn an `index_file` status into whether the message was newly
+    /// indexed, treating `NOTMUCH_STATUS_DUPLICATE_MESSAGE_ID` as success
+    /// (just not a *new* one) rather than an error: it means a message
+    /// with the same id was already indexed and this call merged into it,
+    /// same as notmuch's own `notmuch new` treats it, not a failure to
+    /// index `path`.
+    fn index_file_was_new(status: ffi::notmuch_status_t) -> Result<bool> {
+        if status == ffi::notmuch_status_t::NOTMUCH_STATUS_DUPLICATE_MESSAGE_ID {
+            return Ok(false);
+        }
+
+        try!(status.as_result());
+
+        Ok(true)
+    }
+
+    /// Remove the message at `path` from the index.
+    pub fn remove_message<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+        try!(self.ensure_writable());
+
+        if let Some(cache) = self.cache.as_ref() {
+            if let Some(message) = try!(self.find_message_by_filename(path)) {
+                cache.invalidate(&message.id());
+            }
+        }
+
+        let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_remove_message,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *const libc::c_char,
+                    ) -> ffi::notmuch_status_t,
+                    (self.handle.ptr.as_ptr(), path_str.as_ptr())
+                )
+            }),
+            None => unsafe {
+                ffi::notmuch_database_remove_message(self.handle.ptr.as_ptr(), path_str.as_ptr())
+            },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status =
+            unsafe { ffi::notmuch_database_remove_message(self.handle.ptr.as_ptr(), path_str.as_ptr()) };
+
+        try!(status.as_result());
+
+        Ok(())
+    }
+
+    /// Look up a message by its notmuch message-id.
+    pub fn find_message<'d>(&'d self, message_id: &str) -> Result<Option<Message<'d>>> {
+        let id_str = CString::new(message_id).unwrap();
+
+        let mut message = ptr::null_mut();
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_find_message,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *const libc::c_char,
+                        *mut *mut ffi::notmuch_message_t,
+                    ) -> ffi::notmuch_status_t,
+                    (self.handle.ptr.as_ptr(), id_str.as_ptr(), &mut message)
+                )
+            }),
+            None => unsafe {
+                ffi::notmuch_database_find_message(self.handle.ptr.as_ptr(), id_str.as_ptr(), &mut message)
+            },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe {
+            ffi::notmuch_database_find_message(self.handle.ptr.as_ptr(), id_str.as_ptr(), &mut message)
+        };
+
+        try!(status.as_result());
+
+        if message.is_null() {
+            Ok(None)
+        } else {
+            if let Some(cache) = self.cache.as_ref() {
+                cache.insert(message_id);
+            }
+            Ok(Some(Message::from_ptr(message, self)))
+        }
+    }
+
+    /// Look up a message by the path it was indexed from.
+    pub fn find_message_by_filename<'d, P: AsRef<Path>>(
+        &'d self,
+        path: &P,
+    ) -> Result<Option<Message<'d>>> {
+        let path_str = CString::new(path.as_ref().to_str().unwrap()).unwrap();
+
+        let mut message = ptr::null_mut();
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_find_message_by_filename,
+                    unsafe extern "C" fn(
+                        *mut ffi::notmuch_database_t,
+                        *const libc::c_char,
+                        *mut *mut ffi::notmuch_message_t,
+                    ) -> ffi::notmuch_status_t,
+                    (self.handle.ptr.as_ptr(), path_str.as_ptr(), &mut message)
+                )
+            }),
+            None => unsafe {
+                ffi::notmuch_database_find_message_by_filename(
+                    self.handle.ptr.as_ptr(),
+                    path_str.as_ptr(),
+                    &mut message,
+                )
+            },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe {
+            ffi::notmuch_database_find_message_by_filename(
+                self.handle.ptr.as_ptr(),
+                path_str.as_ptr(),
+                &mut message,
+            )
+        };
+
+        try!(status.as_result());
+
+        if message.is_null() {
+            Ok(None)
+        } else {
+            let message = Message::from_ptr(message, self);
+            if let Some(cache) = self.cache.as_ref() {
+                cache.insert(&message.id());
+            }
+            Ok(Some(message))
+        }
+    }
+
+    /// Opt into the message-id-keyed cache for this handle. A no-op if
+    /// it's already enabled.
+    pub fn enable_message_cache(&mut self) {
+        if self.cache.is_none() {
+            self.cache = Some(MessageCache::new());
+        }
+    }
+
+    /// Look up a message previously seen by this handle via its stable
+    /// [`EnvelopeHash`], requires [`Database::enable_message_cache`] to
+    /// have been called first. Returns `Ok(None)` both when the cache is
+    /// disabled and when the hash is unknown to it — entries are held
+    /// strongly (see [`MessageCache`]) and never expire on their own, so
+    /// "unknown" only happens for a hash the cache never saw, or one
+    /// explicitly dropped via `invalidate`/`clear`.
+    pub fn message_for_hash<'d>(&'d self, hash: EnvelopeHash) -> Result<Option<Message<'d>>> {
+        match self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.id_for_hash(hash))
+        {
+            Some(id) => self.find_message(&id),
+            None => Ok(None),
+        }
+    }
+
+    /// Start a notmuch atomic section: every write made through `self`
+    /// until the returned guard is dropped commits as one Xapian
+    /// transaction, so bulk tag/index operations don't pay for a commit
+    /// per message.
+    pub fn atomic<'d>(&'d self) -> Result<AtomicGuard<'d>> {
+        try!(self.ensure_writable());
+
+        #[cfg(feature = "dynamic")]
+        let status = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_database_begin_atomic,
+                    unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> ffi::notmuch_status_t,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_database_begin_atomic(self.handle.ptr.as_ptr()) },
+        };
+
+        #[cfg(not(feature = "dynamic"))]
+        let status = unsafe { ffi::notmuch_database_begin_atomic(self.handle.ptr.as_ptr()) };
+
+        try!(status.as_result());
+
+        Ok(AtomicGuard { database: self })
+    }
+
+    /// Watch this database's path for filesystem changes, turning bursts
+    /// of inotify events into [`RefreshEvent`](::watch::RefreshEvent)s so
+    /// clients can react to new mail instead of polling.
+    ///
+    /// Needs [`Database::revision`] to detect whether anything actually
+    /// changed, which is only available from v0.21 onward.
+    #[cfg(feature = "v0_21")]
+    pub fn watch<'d>(&'d self) -> Result<::watch::DatabaseWatcher<'d>> {
+        ::watch::DatabaseWatcher::new(self)
+    }
+
+    /// Drop every entry from the message-id cache, if it's enabled. Used
+    /// by [`::watch::DatabaseWatcher`] when a batch of filesystem changes
+    /// can't be cleanly attributed to individual messages.
+    pub(crate) fn clear_message_cache(&self) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.clear();
+        }
+    }
+
+    /// Drop `message_id`'s entry from the message-id cache, if it's
+    /// enabled. Used by [`::watch::DatabaseWatcher`] when a batch of
+    /// filesystem changes is cleanly attributable and classifies one of
+    /// them as a removal, the same way [`Database::remove_message`] does
+    /// for its own caller-driven removals.
+    pub(crate) fn invalidate_message_cache(&self, message_id: &str) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.invalidate(message_id);
+        }
+    }
 }
 
 unsafe impl Send for Database {}
 unsafe impl Sync for Database {}
+
+/// RAII guard for a notmuch atomic section, returned by [`Database::atomic`].
+/// Ends the section on drop, committing everything written through it as
+/// a single Xapian transaction.
+#[derive(Debug)]
+pub struct AtomicGuard<'d> {
+    database: &'d Database,
+}
+
+impl<'d> Drop for AtomicGuard<'d> {
+    fn drop(&mut self) {
+        #[cfg(feature = "dynamic")]
+        match self.database.library() {
+            Some(lib) => {
+                let _ = unsafe {
+                    call!(
+                        lib,
+                        notmuch_database_end_atomic,
+                        unsafe extern "C" fn(*mut ffi::notmuch_database_t) -> ffi::notmuch_status_t,
+                        (self.database.handle.ptr.as_ptr())
+                    )
+                };
+            }
+            None => unsafe {
+                ffi::notmuch_database_end_atomic(self.database.handle.ptr.as_ptr());
+            },
+        }
+
+        #[cfg(not(feature = "dynamic"))]
+        unsafe {
+            ffi::notmuch_database_end_atomic(self.database.handle.ptr.as_ptr())
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_message_id_is_not_new_but_not_an_error() {
+        let was_new =
+            Database::index_file_was_new(ffi::notmuch_status_t::NOTMUCH_STATUS_DUPLICATE_MESSAGE_ID)
+                .unwrap();
+
+        assert_eq!(was_new, false);
+    }
+
+    #[test]
+    fn success_is_new() {
+        let was_new =
+            Database::index_file_was_new(ffi::notmuch_status_t::NOTMUCH_STATUS_SUCCESS).unwrap();
+
+        assert_eq!(was_new, true);
+    }
+
+    #[test]
+    fn other_errors_still_propagate() {
+        let result =
+            Database::index_file_was_new(ffi::notmuch_status_t::NOTMUCH_STATUS_OUT_OF_MEMORY);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn database_ptr_from_ptr_rejects_null() {
+        assert!(DatabasePtr::from_ptr(ptr::null_mut()).is_err());
+    }
+
+    #[test]
+    fn database_ptr_from_ptr_accepts_non_null() {
+        use std::mem;
+
+        // Never dereferenced: from_ptr only checks it against null and
+        // wraps it. Forget the result instead of letting it drop, since
+        // the real Drop impl would pass this fake pointer to
+        // notmuch_database_destroy.
+        let fake = 1 as *mut ffi::notmuch_database_t;
+        let wrapped = DatabasePtr::from_ptr(fake).unwrap();
+
+        assert_eq!(wrapped.ptr.as_ptr(), fake);
+        mem::forget(wrapped);
+    }
+}
@@ -1,19 +1,37 @@
 use std::ops::Drop;
+use std::ptr::NonNull;
 use supercow::Phantomcow;
 
+use error::{Error, Result};
 use ffi;
+#[cfg(feature = "dynamic")]
+use libc;
+#[cfg(feature = "dynamic")]
+use library::NotmuchLibrary;
+#[cfg(feature = "dynamic")]
+use std::sync::Arc;
 use Database;
 use Filenames;
 use FilenamesOwner;
 
 #[derive(Debug)]
 pub(crate) struct DirectoryPtr {
-    pub ptr: *mut ffi::notmuch_directory_t,
+    pub ptr: NonNull<ffi::notmuch_directory_t>,
+}
+
+impl DirectoryPtr {
+    /// Wrap a `notmuch_directory_t*`, failing instead of trusting the
+    /// caller when libnotmuch hands back null.
+    fn from_ptr(ptr: *mut ffi::notmuch_directory_t) -> Result<Self> {
+        NonNull::new(ptr)
+            .map(|ptr| DirectoryPtr { ptr })
+            .ok_or_else(|| Error::from("notmuch returned a null directory handle".to_string()))
+    }
 }
 
 impl Drop for DirectoryPtr {
     fn drop(&mut self) {
-        unsafe { ffi::notmuch_directory_destroy(self.ptr) };
+        unsafe { ffi::notmuch_directory_destroy(self.ptr.as_ptr()) };
     }
 }
 
@@ -21,28 +39,129 @@ impl Drop for DirectoryPtr {
 pub struct Directory<'d> {
     handle: DirectoryPtr,
     marker: Phantomcow<'d, Database>,
+    /// The library backing `marker`'s `Database`, cloned out before it's
+    /// folded into the phantom owner so dynamic calls can still reach it.
+    #[cfg(feature = "dynamic")]
+    library: Option<Arc<NotmuchLibrary>>,
 }
 
 impl<'d> FilenamesOwner for Directory<'d> {}
 
 impl<'d> Directory<'d> {
-    pub fn from_ptr<O: Into<Phantomcow<'d, Database>>>(
+    /// Wrap a `notmuch_directory_t*` freshly returned by libnotmuch,
+    /// failing with a typed `Error` instead of panicking if it's null.
+    pub fn from_ptr<O: Into<Phantomcow<'d, Database>> + AsRef<Database>>(
         ptr: *mut ffi::notmuch_directory_t,
         owner: O,
-    ) -> Directory<'d> {
-        Directory {
-            handle: DirectoryPtr { ptr },
+    ) -> Result<Directory<'d>> {
+        #[cfg(feature = "dynamic")]
+        let library = owner.as_ref().library().cloned();
+
+        Ok(Directory {
+            handle: try!(DirectoryPtr::from_ptr(ptr)),
+            #[cfg(feature = "dynamic")]
+            library,
             marker: owner.into(),
-        }
+        })
+    }
+
+    /// Routed through the dynamically loaded library when this
+    /// directory's database was opened that way (see the note on
+    /// [`crate::Database::path`]). Fallible regardless of `dynamic`,
+    /// like [`crate::Database::create_query`]: notmuch can hand back a
+    /// null `notmuch_filenames_t` on its own, checked via
+    /// [`crate::Database::non_null_or_err`].
+    #[cfg(feature = "dynamic")]
+    pub fn child_directories(&self) -> Result<Filenames<Self>> {
+        let ptr = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_directory_get_child_directories,
+                    unsafe extern "C" fn(*mut ffi::notmuch_directory_t) -> *mut ffi::notmuch_filenames_t,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_directory_get_child_directories(self.handle.ptr.as_ptr()) },
+        };
+        let ptr = try!(Database::non_null_or_err(ptr, "child directories"));
+
+        Ok(Filenames::from_ptr(ptr, self))
+    }
+
+    /// See the note on the `dynamic` variant above.
+    #[cfg(not(feature = "dynamic"))]
+    pub fn child_directories(&self) -> Result<Filenames<Self>> {
+        let ptr = unsafe { ffi::notmuch_directory_get_child_directories(self.handle.ptr.as_ptr()) };
+        let ptr = try!(Database::non_null_or_err(ptr, "child directories"));
+
+        Ok(Filenames::from_ptr(ptr, self))
+    }
+
+    /// See the note on [`Directory::child_directories`].
+    #[cfg(feature = "dynamic")]
+    pub fn child_files(&self) -> Result<Filenames<Self>> {
+        let ptr = match self.library() {
+            Some(lib) => try!(unsafe {
+                call!(
+                    lib,
+                    notmuch_directory_get_child_files,
+                    unsafe extern "C" fn(*mut ffi::notmuch_directory_t) -> *mut ffi::notmuch_filenames_t,
+                    (self.handle.ptr.as_ptr())
+                )
+            }),
+            None => unsafe { ffi::notmuch_directory_get_child_files(self.handle.ptr.as_ptr()) },
+        };
+        let ptr = try!(Database::non_null_or_err(ptr, "child files"));
+
+        Ok(Filenames::from_ptr(ptr, self))
+    }
+
+    /// See the note on [`Directory::child_directories`].
+    #[cfg(not(feature = "dynamic"))]
+    pub fn child_files(&self) -> Result<Filenames<Self>> {
+        let ptr = unsafe { ffi::notmuch_directory_get_child_files(self.handle.ptr.as_ptr()) };
+        let ptr = try!(Database::non_null_or_err(ptr, "child files"));
+
+        Ok(Filenames::from_ptr(ptr, self))
     }
 
-    pub fn child_directories(&self) -> Filenames<Self> {
-        Filenames::from_ptr(
-            unsafe { ffi::notmuch_directory_get_child_directories(self.handle.ptr) },
-            self,
-        )
+    /// The library this directory's owning [`Database`] was opened
+    /// through, if it was opened via `Database::open_with_library` /
+    /// `create_with_library`. Cloned out of the `Database` at construction
+    /// time so it stays alive for as long as this `Directory` does, even
+    /// though `marker` itself only tracks the owner's lifetime.
+    #[cfg(feature = "dynamic")]
+    fn library(&self) -> Option<&Arc<NotmuchLibrary>> {
+        self.library.as_ref()
     }
 }
 
 unsafe impl<'d> Send for Directory<'d> {}
 unsafe impl<'d> Sync for Directory<'d> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn directory_ptr_from_ptr_rejects_null() {
+        assert!(DirectoryPtr::from_ptr(ptr::null_mut()).is_err());
+    }
+
+    #[test]
+    fn directory_ptr_from_ptr_accepts_non_null() {
+        use std::mem;
+
+        // Never dereferenced: from_ptr only checks it against null and
+        // wraps it. Forget the result instead of letting it drop, since
+        // the real Drop impl would pass this fake pointer to
+        // notmuch_directory_destroy.
+        let fake = 1 as *mut ffi::notmuch_directory_t;
+        let wrapped = DirectoryPtr::from_ptr(fake).unwrap();
+
+        assert_eq!(wrapped.ptr.as_ptr(), fake);
+        mem::forget(wrapped);
+    }
+}
@@ -0,0 +1,85 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::sync::Arc;
+
+use libloading::{Library, Symbol};
+
+use error::{Error, Result};
+
+/// Sonames tried, in order, by [`NotmuchLibrary::open_default`] when the
+/// caller doesn't know exactly which one is installed.
+const DEFAULT_SONAMES: &[&str] = &["libnotmuch.so.5", "libnotmuch.so.4", "libnotmuch.so"];
+
+/// A `libnotmuch` resolved at runtime rather than linked at compile time.
+///
+/// Keeping this behind an `Arc` lets a [`Database`](crate::Database) and
+/// every `Directory` derived from it hold a clone, so the library stays
+/// mapped for as long as any of them is alive, and callers can pick an
+/// ABI-specific path at runtime instead of failing to start when notmuch
+/// isn't linkable. `Query` and `Tags` do not hold a clone: only the call
+/// that creates them is resolved through the library.
+pub struct NotmuchLibrary {
+    pub(crate) inner: Library,
+}
+
+impl NotmuchLibrary {
+    /// Load `libnotmuch` from an explicit path or soname.
+    pub fn open<P: AsRef<OsStr>>(path: P) -> Result<Arc<Self>> {
+        let inner = unsafe { Library::new(path) }
+            .map_err(|err| Error::from(format!("failed to load libnotmuch: {}", err)))?;
+
+        Ok(Arc::new(NotmuchLibrary { inner }))
+    }
+
+    /// Try [`DEFAULT_SONAMES`] in order and load the first one that resolves.
+    pub fn open_default() -> Result<Arc<Self>> {
+        for soname in DEFAULT_SONAMES {
+            if let Ok(lib) = Self::open(soname) {
+                return Ok(lib);
+            }
+        }
+
+        Err(Error::from(format!(
+            "could not locate libnotmuch (tried {:?})",
+            DEFAULT_SONAMES
+        )))
+    }
+
+    #[doc(hidden)]
+    pub unsafe fn symbol<T>(&self, name: &[u8]) -> Result<Symbol<T>> {
+        self.inner
+            .get(name)
+            .map_err(|err| Error::from(format!("failed to resolve symbol: {}", err)))
+    }
+}
+
+impl fmt::Debug for NotmuchLibrary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NotmuchLibrary").finish()
+    }
+}
+
+unsafe impl Send for NotmuchLibrary {}
+unsafe impl Sync for NotmuchLibrary {}
+
+/// Resolve the symbol named `$name` (a full `notmuch_*` name) from a
+/// [`NotmuchLibrary`] and call it.
+///
+/// `$ty` is the C function pointer type (matching the declaration in
+/// `ffi`); the symbol lookup happens on every call since `libloading`
+/// symbols borrow from the library and we don't cache them per-handle.
+///
+/// Expands to a `Result<_, Error>`, not the bare return value: a library
+/// that's missing a symbol (e.g. an older ABI that predates a feature we
+/// need) is something callers opted into by choosing `dynamic` loading in
+/// the first place, so it has to come back as a typed `Error` they can
+/// handle, not a panic that takes down the thread that was trying to
+/// fail gracefully.
+#[macro_export]
+macro_rules! call {
+    ($lib:expr, $name:ident, $ty:ty, ($($arg:expr),* $(,)?)) => {{
+        $lib
+            .symbol(concat!(stringify!($name), "\0").as_bytes())
+            .map(|symbol: $crate::libloading::Symbol<$ty>| (symbol)($($arg),*))
+    }};
+}
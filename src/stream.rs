@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+use futures::task::AtomicTask;
+use futures::{Async, Poll, Stream};
+
+use Database;
+use Query;
+use QueryExt;
+use StreamingIteratorExt;
+
+/// Bridges a blocking notmuch `StreamingIterator` onto a `futures::Stream`.
+///
+/// libnotmuch itself is synchronous, so there's no way to poll it without
+/// blocking somewhere; this runs the whole query (starting it, walking
+/// its `StreamingIterator`, everything) on a dedicated thread (the
+/// "blocking pool" an async mail client would otherwise need to bridge to
+/// by hand) and hands ids to the consumer over a channel, waking the
+/// polling task each time one arrives.
+struct QueryStream<T> {
+    receiver: Receiver<Result<T, ()>>,
+    task: Arc<AtomicTask>,
+    done: Arc<AtomicBool>,
+}
+
+impl<T: Send + 'static> QueryStream<T> {
+    /// Spawn `work` on a dedicated thread; every `T` and the eventual
+    /// `Err(())` (if any) it returns is sent to the stream in order, with
+    /// the stream ending right after.
+    fn spawn<F>(work: F) -> Self
+    where
+        F: FnOnce(&mut FnMut(T)) -> Result<(), ()> + Send + 'static,
+    {
+        let (tx, receiver) = channel();
+        let task = Arc::new(AtomicTask::new());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let thread_task = task.clone();
+        let thread_done = done.clone();
+        thread::spawn(move || {
+            let result = work(&mut |item| {
+                let _ = tx.send(Ok(item));
+                thread_task.notify();
+            });
+
+            if let Err(()) = result {
+                let _ = tx.send(Err(()));
+                thread_task.notify();
+            }
+
+            thread_done.store(true, Ordering::SeqCst);
+            thread_task.notify();
+        });
+
+        QueryStream {
+            receiver,
+            task,
+            done,
+        }
+    }
+}
+
+impl<T> Stream for QueryStream<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        self.task.register();
+
+        match self.receiver.try_recv() {
+            Ok(Ok(item)) => Ok(Async::Ready(Some(item))),
+            Ok(Err(())) => Err(()),
+            // The worker may have sent its last items between the
+            // `try_recv` above and setting `done`, so look once more.
+            Err(_) if self.done.load(Ordering::SeqCst) => match self.receiver.try_recv() {
+                Ok(Ok(item)) => Ok(Async::Ready(Some(item))),
+                Ok(Err(())) => Err(()),
+                Err(_) => Ok(Async::Ready(None)),
+            },
+            Err(_) => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// `futures::Stream` adapters over notmuch searches, for async mail
+/// clients that want to drive notmuch searches the same way they drive
+/// IMAP instead of consuming the blocking `StreamingIterator` by hand.
+///
+/// Implemented for `Arc<Database>` rather than a borrowed [`Query`]:
+/// building the query, running it, and walking its `StreamingIterator`
+/// all happen on the background thread, so the only thing that needs to
+/// outlive this call is the `Database` the caller already shares
+/// ownership of, not a borrow tied to this call's stack frame. A `Query`
+/// (or the `Messages`/`Threads` it produces) can't cross onto that thread
+/// itself: it borrows the `Database` for as long as the caller's own
+/// query string's `'d` happens to be, which in the one real caller
+/// ([`Database::create_query<'d>(&'d self, ..)`](crate::Database::create_query))
+/// is tied to a borrow of a normally-scoped `Database`, not `'static`.
+///
+/// Streams ids rather than borrowed `Message`/`Thread` handles for the
+/// same reason: an id is the only part of either that can safely survive
+/// past the background thread's own `Query`.
+pub trait QueryStreamExt {
+    /// Stream the ids of the messages matching `query_string`.
+    fn search_messages_stream(self, query_string: &str) -> Box<Stream<Item = String, Error = ()> + Send>;
+
+    /// Stream the ids of the threads matching `query_string`.
+    fn search_threads_stream(self, query_string: &str) -> Box<Stream<Item = String, Error = ()> + Send>;
+}
+
+impl QueryStreamExt for Arc<Database> {
+    fn search_messages_stream(self, query_string: &str) -> Box<Stream<Item = String, Error = ()> + Send> {
+        let query_string = query_string.to_string();
+
+        Box::new(QueryStream::spawn(move |send| {
+            let query = self.create_query(&query_string).map_err(|_| ())?;
+            let messages = <Query as QueryExt>::search_messages(Arc::new(query)).map_err(|_| ())?;
+            let messages = Arc::new(messages);
+
+            while let Some(message) = StreamingIteratorExt::next(messages.clone()) {
+                send(message.id().to_string());
+            }
+
+            Ok(())
+        }))
+    }
+
+    fn search_threads_stream(self, query_string: &str) -> Box<Stream<Item = String, Error = ()> + Send> {
+        let query_string = query_string.to_string();
+
+        Box::new(QueryStream::spawn(move |send| {
+            let query = self.create_query(&query_string).map_err(|_| ())?;
+            let threads = <Query as QueryExt>::search_threads(Arc::new(query)).map_err(|_| ())?;
+            let threads = Arc::new(threads);
+
+            while let Some(thread) = StreamingIteratorExt::next(threads.clone()) {
+                send(thread.id().to_string());
+            }
+
+            Ok(())
+        }))
+    }
+}
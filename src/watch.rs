@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use libc;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use error::Error;
+use error::Result;
+use Database;
+use DatabaseMode;
+
+/// How long [`DatabaseWatcher`] waits for more inotify events before
+/// giving up and treating whatever arrived as one batch.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// A single logical change observed in the database since the watcher
+/// was started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefreshEvent {
+    /// A message with this id was added.
+    Create(String),
+    /// A message with this id was removed.
+    Remove(String),
+    /// Something changed that couldn't be mapped to individual messages;
+    /// callers should handle this like a full rescan.
+    Rescan,
+}
+
+/// Watches a [`Database`]'s path for filesystem changes and turns
+/// debounced batches of `notify` events into [`RefreshEvent`]s.
+///
+/// This never holds a write lock on the database: notmuch snapshots its
+/// view of the maildir at open time, so each batch is handled by opening
+/// a fresh read-only handle and comparing its
+/// `notmuch_database_get_revision` against the value seen last time,
+/// rather than reusing a long-lived handle that wouldn't see the change.
+pub struct DatabaseWatcher<'d> {
+    database: &'d Database,
+    path: PathBuf,
+    revision: libc::c_ulong,
+    watcher: RecommendedWatcher,
+    events: Receiver<DebouncedEvent>,
+    /// The file -> message-id mapping observed at `self.path` as of the
+    /// last diff, used to classify the next batch into `Create`/`Remove`.
+    known_files: HashMap<PathBuf, String>,
+}
+
+impl<'d> DatabaseWatcher<'d> {
+    #[cfg(feature = "v0_21")]
+    pub(crate) fn new(database: &'d Database) -> Result<Self> {
+        #[cfg(feature = "dynamic")]
+        let path = try!(database.path()).to_path_buf();
+        #[cfg(not(feature = "dynamic"))]
+        let path = database.path().to_path_buf();
+
+        let (tx, events) = channel();
+        let mut watcher =
+            try!(notify::watcher(tx, DEBOUNCE)
+                .map_err(|err| Error::from(format!("failed to start watcher: {}", err))));
+        try!(
+            watcher
+                .watch(&path, RecursiveMode::Recursive)
+                .map_err(|err| Error::from(format!("failed to watch {:?}: {}", path, err)))
+        );
+
+        let known_files = Self::snapshot(database, &path);
+
+        #[cfg(feature = "dynamic")]
+        let revision = try!(database.revision()).revision;
+        #[cfg(not(feature = "dynamic"))]
+        let revision = database.revision().revision;
+
+        Ok(DatabaseWatcher {
+            revision,
+            database,
+            path,
+            watcher,
+            events,
+            known_files,
+        })
+    }
+
+    /// Block until a batch of filesystem events yields at least one
+    /// `RefreshEvent`, draining whatever else arrives in the same burst
+    /// before the revision comparison runs so a burst of saves collapses
+    /// into a single diff.
+    ///
+    /// Named `poll_batch` rather than `next` so it doesn't read as (and
+    /// collide with) `Iterator::next`/`Stream::poll`: this blocks the
+    /// calling thread on `recv()`, which neither of those trait contracts
+    /// allows.
+    #[cfg(feature = "v0_21")]
+    pub fn poll_batch(&mut self) -> Result<Vec<RefreshEvent>> {
+        loop {
+            if self.events.recv().is_err() {
+                return Err(Error::from("watcher channel closed".to_string()));
+            }
+            // Drain the rest of this burst before we look at the database,
+            // so a flurry of saves collapses into a single diff.
+            while self.events.recv_timeout(Duration::from_millis(50)).is_ok() {}
+
+            // Reopen through whatever library (static or dynamically
+            // loaded) the watched handle was opened with, so a `dynamic`
+            // database isn't re-examined against the statically linked
+            // libnotmuch it may not even have.
+            #[cfg(feature = "dynamic")]
+            let reopened = match self.database.library() {
+                Some(lib) => try!(Database::open_with_library(
+                    &self.path,
+                    DatabaseMode::ReadOnly,
+                    lib.clone()
+                )),
+                None => try!(Database::open(&self.path, DatabaseMode::ReadOnly)),
+            };
+
+            #[cfg(not(feature = "dynamic"))]
+            let reopened = try!(Database::open(&self.path, DatabaseMode::ReadOnly));
+
+            #[cfg(feature = "dynamic")]
+            let new_revision = try!(reopened.revision()).revision;
+            #[cfg(not(feature = "dynamic"))]
+            let new_revision = reopened.revision().revision;
+
+            if new_revision == self.revision {
+                continue;
+            }
+
+            let refreshed = self.diff(&reopened);
+            self.revision = new_revision;
+            return Ok(refreshed);
+        }
+    }
+
+    /// Recursively collect every file under `path` together with the
+    /// message id it indexes to, via the `Directory`/`Filenames`
+    /// machinery. Recurses into `child_directories()` as well as
+    /// `child_files()`: a maildir keeps its messages under `cur/`/`new/`,
+    /// not at the watched root, so only walking the root's own files
+    /// would never see them. Returns whether anything along the way
+    /// (a directory that can't be walked, or a file that can't yet be
+    /// resolved to a message id) was left unaccounted for; the caller
+    /// treats that as ambiguous.
+    #[cfg(feature = "v0_21")]
+    fn walk(db: &Database, path: &Path, files: &mut HashMap<PathBuf, String>) -> bool {
+        let directory = match db.directory(path) {
+            Ok(Some(directory)) => directory,
+            _ => return true,
+        };
+
+        let child_files = match directory.child_files() {
+            Ok(child_files) => child_files,
+            Err(_) => return true,
+        };
+
+        let mut ambiguous = false;
+
+        for file in child_files {
+            let file = path.join(&file);
+            match db.find_message_by_filename(&file) {
+                Ok(Some(message)) => {
+                    files.insert(file, message.id().to_string());
+                }
+                _ => ambiguous = true,
+            }
+        }
+
+        let child_directories = match directory.child_directories() {
+            Ok(child_directories) => child_directories,
+            Err(_) => return true,
+        };
+
+        for child in child_directories {
+            if Self::walk(db, &path.join(&child), files) {
+                ambiguous = true;
+            }
+        }
+
+        ambiguous
+    }
+
+    /// See [`DatabaseWatcher::walk`]. A file notmuch hasn't indexed yet
+    /// (or no longer recognizes) is simply left out of the map; the
+    /// initial snapshot doesn't need to distinguish that from a directory
+    /// it couldn't walk, since there's no `known_files` yet to diff
+    /// against.
+    #[cfg(feature = "v0_21")]
+    fn snapshot(db: &Database, path: &PathBuf) -> HashMap<PathBuf, String> {
+        let mut files = HashMap::new();
+        Self::walk(db, path, &mut files);
+        files
+    }
+
+    /// Classify what changed by walking `self.path` (recursively) through
+    /// the freshly reopened handle and comparing against
+    /// `self.known_files`: a message id present now but not before is a
+    /// `Create`, the reverse is a `Remove` (which also invalidates that id
+    /// in the owning database's message-id cache, the same as calling
+    /// [`Database::remove_message`] directly would).
+    ///
+    /// Diffed by **id**, not by path: notmuch/maildir rewrite a message's
+    /// filename on basically every read or tag change (the new/ -> cur/
+    /// transition, flag letters in the name, …), so the same id routinely
+    /// appears at a different path between two snapshots without the
+    /// message itself having been created or removed. Comparing paths
+    /// directly would misreport every such rename as a `Remove` + `Create`
+    /// pair and wrongly invalidate a cache entry the id-stability
+    /// guarantee is supposed to survive.
+    ///
+    /// Falls back to `Rescan` (and clears the whole cache, since we can no
+    /// longer vouch for any entry in it) when [`DatabaseWatcher::walk`]
+    /// reports anything ambiguous.
+    #[cfg(feature = "v0_21")]
+    fn diff(&mut self, reopened: &Database) -> Vec<RefreshEvent> {
+        let mut current = HashMap::new();
+        let ambiguous = Self::walk(reopened, &self.path, &mut current);
+
+        if ambiguous {
+            self.known_files = current;
+            self.database.clear_message_cache();
+            return vec![RefreshEvent::Rescan];
+        }
+
+        let known_ids: HashSet<&String> = self.known_files.values().collect();
+        let current_ids: HashSet<&String> = current.values().collect();
+
+        let mut events: Vec<RefreshEvent> = known_ids
+            .iter()
+            .filter(|id| !current_ids.contains(**id))
+            .map(|id| {
+                self.database.invalidate_message_cache(id);
+                RefreshEvent::Remove((*id).clone())
+            })
+            .collect();
+
+        events.extend(
+            current_ids
+                .iter()
+                .filter(|id| !known_ids.contains(**id))
+                .map(|id| RefreshEvent::Create((*id).clone())),
+        );
+
+        self.known_files = current;
+
+        if events.is_empty() {
+            // The revision advanced but nothing under `self.path` changed
+            // in a way we can see (e.g. tag-only changes, or every id
+            // simply moved to a new path); let the caller decide how to
+            // handle it rather than reporting nothing.
+            events.push(RefreshEvent::Rescan);
+        }
+
+        events
+    }
+}
+
+#[cfg(feature = "v0_21")]
+impl<'d> Drop for DatabaseWatcher<'d> {
+    fn drop(&mut self) {
+        let _ = self.watcher.unwatch(&self.path);
+    }
+}
@@ -15,7 +15,12 @@ fn main() {
         notmuch::DatabaseMode::ReadOnly,
     ) {
         Ok(db) => {
-            #[cfg(feature = "v0_21")]
+            #[cfg(all(feature = "v0_21", feature = "dynamic"))]
+            {
+                let rev = db.revision().unwrap();
+                println!("db revision: {:?}", rev);
+            }
+            #[cfg(all(feature = "v0_21", not(feature = "dynamic")))]
             {
                 let rev = db.revision();
                 println!("db revision: {:?}", rev);